@@ -0,0 +1,59 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **********************************************************************************************************************/
+
+//! # Board specific addressing
+//!
+//! The peripheral MMIO base address as well as the translation between an ARM physical address and the bus
+//! address the VideoCore expects to find in a mailbox message differ between Raspberry Pi models. This module
+//! picks both based on the active `ruspiro_pi1`/`ruspiro_pi3`/`ruspiro_pi4_experimental` feature.
+//!
+//! `ruspiro_pi4_experimental` is named to flag that its [`GPU_ALIAS`] has only been confirmed against community
+//! reports, not against real BCM2711 hardware from this crate; the pi1/pi3 path is the well established one.
+//!
+
+#[cfg(feature = "ruspiro_pi1")]
+pub(crate) const PERIPHERAL_BASE: u32 = 0x2000_0000;
+
+#[cfg(feature = "ruspiro_pi3")]
+pub(crate) const PERIPHERAL_BASE: u32 = 0x3F00_0000;
+
+#[cfg(feature = "ruspiro_pi4_experimental")]
+pub(crate) const PERIPHERAL_BASE: u32 = 0xFE00_0000;
+
+// BCM2835/BCM2837 route GPU accessible memory through an L1/L2 cache alias, mailbox messages need to target
+// the uncached "C" alias so the VideoCore does not see stale, ARM side cached data.
+// See https://github.com/raspberrypi/firmware/wiki/Accessing-mailboxes
+#[cfg(any(feature = "ruspiro_pi1", feature = "ruspiro_pi3"))]
+const GPU_ALIAS: u32 = 0xC000_0000;
+
+// BCM2711 (Pi4) dropped the legacy VC4 cache alias addressing scheme, the VideoCore MMU is configured by the
+// firmware to see the same physical address ARM does, so no alias bits need to be OR-ed in.
+// See https://forums.raspberrypi.com/viewtopic.php?t=244411 (community confirmation for the bare-metal case,
+// the upstream mailbox wiki above predates the Pi4) - this has not been verified against real BCM2711
+// hardware from this crate, re-check if Pi4 mailbox round-trips come back with a bad buffer address.
+#[cfg(feature = "ruspiro_pi4_experimental")]
+const GPU_ALIAS: u32 = 0x0000_0000;
+
+/// Convert an ARM physical address into the bus address the VideoCore expects to see in a mailbox message, for
+/// the currently active board.
+pub(crate) fn bus_address(arm_address: u32) -> u32 {
+    // the alias is OR-ed in on the assumption that `arm_address` does not already use these bits; on pi1/pi3
+    // this only holds for addresses within the first 1GiB of RAM
+    #[cfg(any(feature = "ruspiro_pi1", feature = "ruspiro_pi3"))]
+    debug_assert_eq!(
+        arm_address & GPU_ALIAS,
+        0,
+        "arm_address already sets bits reserved for the GPU cache alias"
+    );
+
+    arm_address | GPU_ALIAS
+}
+
+/// Inverse of [`bus_address`], turns a VC bus address read back from the mailbox into an ARM physical address.
+pub(crate) fn arm_address(bus_address: u32) -> u32 {
+    bus_address ^ GPU_ALIAS
+}
@@ -0,0 +1,32 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **********************************************************************************************************************/
+
+//! # Mailbox errors
+//!
+//! Failures while exchanging a message with the VideoCore over the mailbox are not all equal - the firmware
+//! distinguishes a fully successful response from one it only partially processed, which this crate surfaces
+//! rather than collapsing both into a single flat error.
+//!
+
+/// Errors that can occur while sending a message through the mailbox property tag interface
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum MailboxError {
+    /// The firmware only partially processed the message (its `ResponseError` state). Tags whose response bit
+    /// is set may still carry a valid response and can be salvaged.
+    PartialResponse,
+    /// The message could not be processed by the receiver at all
+    ErrorResponse,
+    /// A response was read back from mailbox 0 but addressed a different channel than expected
+    ChannelMismatch,
+    /// A bounded wait exceeded its maximum number of spins without the mailbox becoming ready
+    Timeout,
+    /// `send_message_irq` was called for a channel that already has a request in flight
+    ChannelBusy,
+    /// `PropertyTagResponse::get::<T>` was called with a `T` larger than the tag's reserved value buffer
+    TagSizeMismatch,
+}
@@ -0,0 +1,107 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **********************************************************************************************************************/
+
+//! # Framebuffer property tags
+//!
+//! Driving the framebuffer over the property tag channel (`MailboxChannel::PropertyTagsVc`, not the legacy
+//! `MailboxChannel::FrameBuffer`) requires the VideoCore to be told the physical and
+//! virtual screen size, the color depth and the virtual offset, after which it allocates and returns the
+//! buffer itself. This module declares those tags and provides [`allocate_framebuffer`] to submit them together
+//! in a single property tag message.
+//!
+
+use crate::board::arm_address;
+use crate::error::MailboxError;
+use crate::interface::MailboxResult;
+use crate::tags::PropertyTagBuilder;
+
+const TAG_SET_PHYSICAL_SIZE: u32 = 0x0004_8003;
+const TAG_SET_VIRTUAL_SIZE: u32 = 0x0004_8004;
+const TAG_SET_DEPTH: u32 = 0x0004_8005;
+const TAG_SET_VIRTUAL_OFFSET: u32 = 0x0004_8009;
+const TAG_ALLOCATE_BUFFER: u32 = 0x0004_0001;
+const TAG_GET_PITCH: u32 = 0x0004_0008;
+
+/// Default alignment, in bytes, requested for the allocated framebuffer
+const BUFFER_ALIGNMENT: u32 = 16;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Size {
+    width: u32,
+    height: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Depth {
+    depth: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Offset {
+    x: u32,
+    y: u32,
+}
+
+/// Request value is the alignment the VideoCore should place the buffer at, both fields are overwritten in
+/// place with the allocated buffer's base address and size once the response comes back.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct AllocateBuffer {
+    base: u32,
+    size: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Pitch {
+    pitch: u32,
+}
+
+/// The framebuffer the VideoCore allocated in response to [`allocate_framebuffer`]
+pub struct FrameBufferInfo {
+    /// ARM physical address of the allocated framebuffer
+    pub base: u32,
+    /// size of the allocated framebuffer in bytes
+    pub size: u32,
+    /// number of bytes per row of pixels
+    pub pitch: u32,
+}
+
+/// Set the physical and virtual screen size, color depth and virtual offset and let the VideoCore allocate a
+/// framebuffer matching them, all in a single property tag message.
+pub fn allocate_framebuffer(width: u32, height: u32, depth: u32) -> MailboxResult<FrameBufferInfo> {
+    let mut builder = PropertyTagBuilder::new();
+    builder.add_tag(TAG_SET_PHYSICAL_SIZE, Size { width, height });
+    builder.add_tag(TAG_SET_VIRTUAL_SIZE, Size { width, height });
+    builder.add_tag(TAG_SET_DEPTH, Depth { depth });
+    builder.add_tag(TAG_SET_VIRTUAL_OFFSET, Offset { x: 0, y: 0 });
+    let buffer_tag = builder.add_tag(
+        TAG_ALLOCATE_BUFFER,
+        AllocateBuffer {
+            base: BUFFER_ALIGNMENT,
+            size: 0,
+        },
+    );
+    let pitch_tag = builder.add_tag(TAG_GET_PITCH, Pitch { pitch: 0 });
+
+    let response = builder.send()?;
+    if response.is_partial() {
+        return Err(MailboxError::PartialResponse);
+    }
+
+    let allocated: AllocateBuffer = response.get(buffer_tag)?;
+    let pitch: Pitch = response.get(pitch_tag)?;
+
+    Ok(FrameBufferInfo {
+        base: arm_address(allocated.base),
+        size: allocated.size,
+        pitch: pitch.pitch,
+    })
+}
@@ -15,15 +15,13 @@ extern crate alloc;
 use alloc::*;
 
 use ruspiro_cache as cache;
-use ruspiro_console::*;
 use ruspiro_register::define_mmio_register;
 
-// MMIO base address for peripherals
-#[cfg(feature = "ruspiro_pi3")]
-const PERIPHERAL_BASE: u32 = 0x3F00_0000;
+use crate::board::{arm_address, bus_address, PERIPHERAL_BASE};
+use crate::error::MailboxError;
 
 // Mailbox MMIO base address
-const MAILBOX_BASE: u32 = PERIPHERAL_BASE + 0x0000_B880;
+pub(crate) const MAILBOX_BASE: u32 = PERIPHERAL_BASE + 0x0000_B880;
 
 /// Definition of the different message stats/types used in the mailbox interface
 #[repr(u32)]
@@ -45,7 +43,7 @@ pub enum MessageState {
 pub enum MailboxChannel {
     /// Power management channel
     PowerMgmt = 0x0,
-    /// Framebuffer channel (shall not be used)
+    /// Legacy framebuffer channel, superseded by the framebuffer property tags sent over `PropertyTagsVc`
     FrameBuffer = 0x1,
     /// Virtual UART channel
     VirtualUart = 0x2,
@@ -62,40 +60,64 @@ pub trait MailboxMessage {
 }
 
 /// Type alias for Results of the functions in this module
-pub type MailboxResult<T> = Result<T, &'static str>;
-
-/// Function to send a specific message to the mailbox channel given
-/// The mailbox interface does update the memory location of the message send. Therefor the function returns
-/// Ok with the updated message in case of a success
-// never inline, if inlined the compiler seem to mess up something and no mailbox call succeeds
-//#[inline(never)]
-pub(crate) fn send_message<T: MailboxMessage>(
+pub type MailboxResult<T> = Result<T, MailboxError>;
+
+/// Send `message` on `channel` and wait for its response, bounding the FULL/EMPTY wait loops to `max_spins` iterations each
+/// and returns [`MailboxError::Timeout`] instead of spinning forever, so a wedged VideoCore does not hang the
+/// calling core.
+///
+/// `message` is moved onto the heap rather than read back from this call's stack frame. If the wait for the
+/// write side times out nothing was ever handed to the VideoCore, so the allocation is freed immediately. But
+/// if the write itself went through and it is the wait for the response that times out, the VideoCore may
+/// still complete the write-back at an arbitrary later point; reading the result out of a stack frame that
+/// has since been reused by the caller would silently corrupt whatever now lives there, so in that case the
+/// heap allocation is deliberately leaked instead of freed, mirroring how `irq.rs` keeps a boxed message alive
+/// until its response arrives.
+pub fn try_send_message<T: MailboxMessage>(
     channel: MailboxChannel,
-    mut message: T,
+    message: T,
+    max_spins: u32,
 ) -> MailboxResult<T> {
-    let msg_ptr: *mut T = &mut message;
-    let msg_ptr_uncached: u32 = (msg_ptr as u32) | 0xC000_0000;
+    let msg_ptr: *mut T = alloc::boxed::Box::into_raw(alloc::boxed::Box::new(message));
+    let msg_ptr_uncached: u32 = bus_address(msg_ptr as u32);
 
     cache::cleaninvalidate();
-    write(channel, msg_ptr_uncached)?;
-    read(channel)?;
+    if let Err(err) = write_bounded(channel, msg_ptr_uncached, max_spins) {
+        // nothing was ever sent, reclaim and drop the box rather than leaking it
+        unsafe { drop(alloc::boxed::Box::from_raw(msg_ptr)) };
+        return Err(err);
+    }
+
+    // from here on the VideoCore may already be processing, or have completed, the write-back into `msg_ptr`;
+    // on Err below leak it rather than freeing memory it might still write back into
+    read_bounded(channel, max_spins)?;
     cache::cleaninvalidate();
-    // at this point the property tag message memory has been changed under the hood
-    // that rust is not aware of, so optimizations might do things that will loose this fact
-    // so read this memory location back into the corresponding message type T
-    let result_ptr = (msg_ptr_uncached ^ 0xC000_0000) as *mut T;
-    //println!("result/origin ptr: {:X} / {:X}", result_ptr as usize, msg_ptr as usize);
-    let result = unsafe { core::ptr::read_volatile(result_ptr) };
+
+    let result_ptr = arm_address(msg_ptr_uncached) as *mut T;
+    let result = *unsafe { alloc::boxed::Box::from_raw(result_ptr) };
     let result_state = result.get_state();
     if result_state == MessageState::ResponseOk as u32 {
         Ok(result)
+    } else if result_state == MessageState::ResponseError as u32 {
+        Err(MailboxError::PartialResponse)
     } else {
-        Err("unable to send mailbox property tag message.")
+        Err(MailboxError::ErrorResponse)
     }
 }
 
+/// Plumbing behind the property tag buffer builder: hand an already assembled,
+/// uncached message pointer to the VideoCore on `channel` and wait for it to come back.
+pub(crate) fn send_ptr(channel: MailboxChannel, msg_ptr_uncached: u32) -> MailboxResult<()> {
+    cache::cleaninvalidate();
+    write_bounded(channel, msg_ptr_uncached, u32::MAX)?;
+    read_bounded(channel, u32::MAX)?;
+    cache::cleaninvalidate();
+    Ok(())
+}
+
 define_mmio_register! [
     MAILBOX0_READ<ReadOnly<u32>@(MAILBOX_BASE + 0x00)>,
+    MAILBOX0_PEEK<ReadOnly<u32>@(MAILBOX_BASE + 0x10)>,
     MAILBOX0_STATUS<ReadOnly<u32>@(MAILBOX_BASE + 0x18)>,
     MAILBOX1_WRITE<WriteOnly<u32>@(MAILBOX_BASE + 0x20)>,
     MAILBOX1_STATUS<ReadOnly<u32>@(MAILBOX_BASE + 0x38)>
@@ -104,21 +126,69 @@ define_mmio_register! [
 const MAILBOX_FULL: u32 = 0x8000_0000; // status register value if the mailbox is already full
 const MAILBOX_EMPTY: u32 = 0x4000_0000; // status register value if the mailbox is empty
 
+/// Inspect the value currently at the front of the mailbox 0 FIFO without popping it, so a caller can check
+/// whether a response for `channel` is already waiting before committing to a blocking [`send_ptr`]. Returns
+/// `Ok(None)` if the FIFO is empty or its front entry does not address `channel`.
+pub fn peek(channel: MailboxChannel) -> MailboxResult<Option<u32>> {
+    if (MAILBOX0_STATUS::Register.get() & MAILBOX_EMPTY) != 0x0 {
+        return Ok(None);
+    }
+    let data = MAILBOX0_PEEK::Register.get();
+    if (data & 0xF) == channel as u32 {
+        Ok(Some(data & 0xFFFF_FFF0))
+    } else {
+        Ok(None)
+    }
+}
+
 #[inline(always)]
 fn read(channel: MailboxChannel) -> MailboxResult<u32> {
+    read_bounded(channel, u32::MAX)
+}
+
+#[inline(always)]
+fn read_bounded(channel: MailboxChannel, max_spins: u32) -> MailboxResult<u32> {
+    let mut spins = 0u32;
     loop {
-        while (MAILBOX0_STATUS::Register.get() & MAILBOX_EMPTY) != 0x0 {}
+        while (MAILBOX0_STATUS::Register.get() & MAILBOX_EMPTY) != 0x0 {
+            spins += 1;
+            if spins >= max_spins {
+                return Err(MailboxError::Timeout);
+            }
+        }
         let data = MAILBOX0_READ::Register.get();
         if (data & 0xF) == channel as u32 {
             return Ok(data & 0xFFFF_FFF0);
         }
+        spins += 1;
+        if spins >= max_spins {
+            return Err(MailboxError::Timeout);
+        }
     }
 }
 
 #[inline(always)]
-fn write(channel: MailboxChannel, data: u32) -> MailboxResult<()> {
-    while (MAILBOX1_STATUS::Register.get() & MAILBOX_FULL) != 0x0 {}
+pub(crate) fn write(channel: MailboxChannel, data: u32) -> MailboxResult<()> {
+    write_bounded(channel, data, u32::MAX)
+}
+
+#[inline(always)]
+fn write_bounded(channel: MailboxChannel, data: u32, max_spins: u32) -> MailboxResult<()> {
+    let mut spins = 0u32;
+    while (MAILBOX1_STATUS::Register.get() & MAILBOX_FULL) != 0x0 {
+        spins += 1;
+        if spins >= max_spins {
+            return Err(MailboxError::Timeout);
+        }
+    }
     let value = (data & 0xFFFF_FFF0) | ((channel as u8) & 0xF) as u32;
     MAILBOX1_WRITE::Register.set(value);
     Ok(())
 }
+
+/// Raw read of the mailbox 0 read register, used by the interrupt driven completion path which already knows
+/// a response is waiting and does not need to spin on `MAILBOX0_STATUS`
+#[cfg(feature = "ruspiro_mailbox_irq")]
+pub(crate) fn mailbox0_read_raw() -> u32 {
+    MAILBOX0_READ::Register.get()
+}
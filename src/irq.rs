@@ -0,0 +1,174 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **********************************************************************************************************************/
+
+//! # Interrupt driven mailbox completion
+//!
+//! Mailbox 0 (VideoCore -> ARM) is the only mailbox wired to a CPU interrupt. This module enables that interrupt
+//! and keeps a small table of in-flight requests so a completing message can be dispatched to its waiter instead
+//! of requiring the caller to busy-poll `MAILBOX0_STATUS`. Only compiled in if the `ruspiro_mailbox_irq` feature
+//! is active, bare-polling users do not pull any of this in.
+//!
+
+use crate::board::{arm_address, bus_address};
+use crate::error::MailboxError;
+use crate::interface::{mailbox0_read_raw, MailboxChannel, MailboxMessage, MailboxResult, MessageState, MAILBOX_BASE};
+use ruspiro_cache as cache;
+use ruspiro_register::define_mmio_register;
+
+define_mmio_register![
+    MAILBOX0_CONFIG<ReadWrite<u32>@(MAILBOX_BASE + 0x1C)>
+];
+
+/// Bit in `MAILBOX0_CONFIG` that raises the mailbox 0 interrupt whenever the ARM facing read FIFO is no
+/// longer empty
+const DATA_IRQ_ENABLE: u32 = 0x1;
+
+/// Bookkeeping for a single message sent through [`send_message_irq`] that is still waiting for its response
+#[derive(Copy, Clone)]
+struct PendingRequest {
+    /// the (uncached) pointer of the message buffer handed to the mailbox, as read back from `MAILBOX0_READ`
+    msg_ptr: u32,
+    /// address of the caller provided callback, type erased as the table needs to hold callbacks for any `T`
+    callback_addr: usize,
+    /// trampoline that knows how to turn `msg_ptr`/`callback_addr` back into the concrete `T` and invoke it
+    complete: fn(u32, usize),
+}
+
+// one slot per possible 4-bit channel value, indexed by the channel the request has been sent on
+static mut PENDING: [Option<PendingRequest>; 16] = [None; 16];
+
+/// Send a message to the given mailbox channel without blocking the caller. Instead of spinning on
+/// `MAILBOX0_STATUS` the response is picked up from [`mailbox_irq_handler`], which is expected to be wired up
+/// to the mailbox 0 interrupt by the caller, and `callback` is invoked with the result once it arrives.
+///
+/// The message is moved onto the heap so its memory stays valid until the VideoCore has written back the
+/// response, as this function returns long before that happens.
+///
+/// Only one request per `channel` can be in flight at a time. Calling this again for a channel that already
+/// has a pending request fails with [`MailboxError::ChannelBusy`] instead of silently overwriting it, which
+/// would otherwise leak the first request's boxed message and drop its callback without ever invoking it.
+pub fn send_message_irq<T: MailboxMessage>(
+    channel: MailboxChannel,
+    message: T,
+    callback: fn(MailboxResult<T>),
+) -> MailboxResult<()> {
+    let msg_ptr: *mut T = alloc::boxed::Box::into_raw(alloc::boxed::Box::new(message));
+    let msg_ptr_uncached: u32 = bus_address(msg_ptr as u32);
+    let idx = (channel as u8 & 0xF) as usize;
+
+    // PENDING is shared with mailbox_irq_handler, which can run at any point once the irq is enabled, so
+    // touching a slot must happen with interrupts masked
+    let already_pending = critical_section(|| unsafe {
+        if PENDING[idx].is_some() {
+            true
+        } else {
+            PENDING[idx] = Some(PendingRequest {
+                msg_ptr: msg_ptr_uncached,
+                callback_addr: callback as usize,
+                complete: complete_trampoline::<T>,
+            });
+            false
+        }
+    });
+
+    if already_pending {
+        // nobody will ever claim this box, reclaim and drop it rather than leaking it
+        unsafe { drop(alloc::boxed::Box::from_raw(msg_ptr)) };
+        return Err(MailboxError::ChannelBusy);
+    }
+
+    cache::cleaninvalidate();
+    enable_irq();
+    crate::interface::write(channel, msg_ptr_uncached)
+}
+
+/// To be called from the application's mailbox 0 interrupt handler. Reads `MAILBOX0_READ`, matches the low 4
+/// bits against the channel of a request registered by [`send_message_irq`] and wakes its waiter, if any.
+///
+/// Returns [`MailboxError::ChannelMismatch`] if the completed channel has no pending request registered for
+/// it, rather than silently dropping the response.
+pub fn mailbox_irq_handler() -> MailboxResult<()> {
+    let data = mailbox0_read_raw();
+    let idx = (data & 0xF) as usize;
+    let msg_ptr = data & 0xFFFF_FFF0;
+
+    let pending = critical_section(|| unsafe { PENDING[idx].take() });
+    match pending {
+        Some(pending) => {
+            (pending.complete)(msg_ptr, pending.callback_addr);
+            Ok(())
+        }
+        None => Err(MailboxError::ChannelMismatch),
+    }
+}
+
+fn enable_irq() {
+    MAILBOX0_CONFIG::Register.set(MAILBOX0_CONFIG::Register.get() | DATA_IRQ_ENABLE);
+}
+
+/// Run `f` with IRQs masked, so it cannot be interrupted by [`mailbox_irq_handler`] while it touches `PENDING`.
+///
+/// Saves the interrupt mask as it was on entry and restores it afterwards, rather than unconditionally
+/// re-enabling IRQs. `mailbox_irq_handler` itself may call this (via [`send_message_irq`] reacting to its own
+/// completion callback) from inside the IRQ, where the CPU has already masked IRQs on entry; blindly
+/// re-enabling here would unmask them early and allow the handler to be re-entered.
+#[inline(always)]
+fn critical_section<F: FnOnce() -> R, R>(f: F) -> R {
+    let prior_mask = disable_irq();
+    let result = f();
+    restore_irq(prior_mask);
+    result
+}
+
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+fn disable_irq() -> u64 {
+    let prior_daif: u64;
+    unsafe { core::arch::asm!("mrs {0}, daif", "msr daifset, #2", out(reg) prior_daif) };
+    prior_daif
+}
+
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+fn restore_irq(prior_daif: u64) {
+    unsafe { core::arch::asm!("msr daif, {0}", in(reg) prior_daif) };
+}
+
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+fn disable_irq() -> u32 {
+    let prior_cpsr: u32;
+    unsafe { core::arch::asm!("mrs {0}, cpsr", "cpsid i", out(reg) prior_cpsr) };
+    prior_cpsr
+}
+
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+fn restore_irq(prior_cpsr: u32) {
+    const CPSR_IRQ_MASK: u32 = 1 << 7;
+    if (prior_cpsr & CPSR_IRQ_MASK) == 0 {
+        unsafe { core::arch::asm!("cpsie i") };
+    }
+}
+
+/// Monomorphized per `T`, this turns the type erased pending request back into the concrete message type,
+/// reconstructs and invokes the caller provided callback with the outcome.
+fn complete_trampoline<T: MailboxMessage>(msg_ptr: u32, callback_addr: usize) {
+    cache::cleaninvalidate();
+    let result_ptr = arm_address(msg_ptr) as *mut T;
+    let result = *unsafe { alloc::boxed::Box::from_raw(result_ptr) };
+    let result_state = result.get_state();
+
+    let callback: fn(MailboxResult<T>) = unsafe { core::mem::transmute(callback_addr) };
+    if result_state == MessageState::ResponseOk as u32 {
+        callback(Ok(result));
+    } else if result_state == MessageState::ResponseError as u32 {
+        callback(Err(MailboxError::PartialResponse));
+    } else {
+        callback(Err(MailboxError::ErrorResponse));
+    }
+}
@@ -0,0 +1,34 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **********************************************************************************************************************/
+#![no_std]
+
+//! # ruspiro-mailbox
+//!
+//! Abstraction of the Raspberry Pi mailbox property tag interface used to exchange information and commands
+//! with the VideoCore.
+//!
+
+extern crate alloc;
+
+mod board;
+
+mod error;
+pub use error::*;
+
+mod interface;
+pub use interface::*;
+
+mod tags;
+pub use tags::*;
+
+mod framebuffer;
+pub use framebuffer::*;
+
+#[cfg(feature = "ruspiro_mailbox_irq")]
+mod irq;
+#[cfg(feature = "ruspiro_mailbox_irq")]
+pub use irq::*;
@@ -0,0 +1,212 @@
+/***********************************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **********************************************************************************************************************/
+
+//! # Composable property tag message builder
+//!
+//! The VideoCore firmware accepts several property tags in a single property tag message, which allows batching
+//! multiple queries (e.g. board revision, memory split, clock rates) into one mailbox round-trip instead of
+//! sending a dedicated `T: MailboxMessage` for each of them. This module assembles the standard tag buffer
+//! layout - a leading size and request/response code, followed by one or more tags and a terminating end tag -
+//! and sends it through the property tag channel.
+//!
+
+use alloc::alloc::{alloc, dealloc, handle_alloc_error, Layout};
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use crate::board::bus_address;
+use crate::error::MailboxError;
+use crate::interface::{send_ptr, MailboxChannel, MailboxResult, MessageState};
+
+/// Tag id marking the end of the tag list
+const TAG_END: u32 = 0x0;
+/// Bit the firmware sets in a tag's request/response code word once it has written its response into the value
+/// bytes (the low 31 bits then hold the response length)
+const TAG_RESPONSE_BIT: u32 = 0x8000_0000;
+
+/// Handle to a tag added to a [`PropertyTagBuilder`], used to read back its response after [`PropertyTagBuilder::send`]
+#[derive(Copy, Clone)]
+pub struct TagHandle(usize);
+
+struct Tag {
+    tag_id: u32,
+    value_buffer_size: u32,
+    value: Vec<u8>,
+    /// filled in by `send`: offset of this tag's id word within the assembled buffer
+    buffer_offset: usize,
+}
+
+/// A 16-byte aligned heap buffer, as required by the mailbox property tag interface
+struct AlignedBuffer {
+    ptr: *mut u8,
+    len: usize,
+    layout: Layout,
+}
+
+impl AlignedBuffer {
+    fn new(len: usize) -> Self {
+        let layout = Layout::from_size_align(len, 16).expect("invalid property tag buffer layout");
+        let ptr = unsafe { alloc(layout) };
+        if ptr.is_null() {
+            // unlike `Box`/`Vec`, the raw alloc API does not abort for us on failure
+            handle_alloc_error(layout);
+        }
+        unsafe { core::ptr::write_bytes(ptr, 0, len) };
+        AlignedBuffer { ptr, len, layout }
+    }
+
+    fn write_u32(&mut self, offset: usize, value: u32) {
+        unsafe { core::ptr::write_volatile((self.ptr.add(offset)) as *mut u32, value) };
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((self.ptr.add(offset)) as *const u32) }
+    }
+
+    fn write_bytes(&mut self, offset: usize, bytes: &[u8]) {
+        unsafe { core::ptr::copy_nonoverlapping(bytes.as_ptr(), self.ptr.add(offset), bytes.len()) };
+    }
+
+    fn read_bytes(&self, offset: usize, len: usize) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr.add(offset), len) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr, self.layout) };
+    }
+}
+
+/// Builds a single property tag message out of one or more tags, so several property queries can be batched
+/// into one mailbox round-trip.
+pub struct PropertyTagBuilder {
+    tags: Vec<Tag>,
+}
+
+impl Default for PropertyTagBuilder {
+    fn default() -> Self {
+        PropertyTagBuilder { tags: Vec::new() }
+    }
+}
+
+impl PropertyTagBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a tag to the message. `request_value` is copied into the tag's value bytes as-is, so for tags that
+    /// only read data back from the VideoCore it should be a zeroed instance of a type large enough to hold
+    /// the expected response.
+    pub fn add_tag<T: Copy>(&mut self, tag_id: u32, request_value: T) -> TagHandle {
+        let value_buffer_size = align4(size_of::<T>() as u32);
+        let mut value = alloc::vec![0u8; value_buffer_size as usize];
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                &request_value as *const T as *const u8,
+                value.as_mut_ptr(),
+                size_of::<T>(),
+            )
+        };
+
+        self.tags.push(Tag {
+            tag_id,
+            value_buffer_size,
+            value,
+            buffer_offset: 0,
+        });
+        TagHandle(self.tags.len() - 1)
+    }
+
+    /// Assemble the tag buffer and send it as a single property tag message to the VideoCore.
+    pub fn send(mut self) -> MailboxResult<PropertyTagResponse> {
+        let mut size = 12u32; // leading buffer size + request/response code + end tag
+        for tag in &self.tags {
+            size += 12 + tag.value_buffer_size; // tag_id + value_buffer_size + req/resp code + value bytes
+        }
+
+        let mut buffer = AlignedBuffer::new(size as usize);
+        buffer.write_u32(0, size);
+        buffer.write_u32(4, MessageState::Request as u32);
+
+        let mut offset = 8usize;
+        for tag in &mut self.tags {
+            tag.buffer_offset = offset;
+            buffer.write_u32(offset, tag.tag_id);
+            buffer.write_u32(offset + 4, tag.value_buffer_size);
+            buffer.write_u32(offset + 8, 0);
+            buffer.write_bytes(offset + 12, &tag.value);
+            offset += 12 + tag.value_buffer_size as usize;
+        }
+        buffer.write_u32(offset, TAG_END);
+
+        let msg_ptr_uncached = bus_address(buffer.ptr as u32);
+        send_ptr(MailboxChannel::PropertyTagsVc, msg_ptr_uncached)?;
+
+        let response_code = buffer.read_u32(4);
+        if response_code != MessageState::ResponseOk as u32 && response_code != MessageState::ResponseError as u32 {
+            return Err(MailboxError::ErrorResponse);
+        }
+
+        Ok(PropertyTagResponse {
+            is_partial: response_code == MessageState::ResponseError as u32,
+            buffer,
+            tags: self.tags,
+        })
+    }
+}
+
+/// Result of sending a [`PropertyTagBuilder`], allowing the response of each tag to be read back through the
+/// [`TagHandle`] it was added with.
+pub struct PropertyTagResponse {
+    is_partial: bool,
+    buffer: AlignedBuffer,
+    tags: Vec<Tag>,
+}
+
+impl PropertyTagResponse {
+    /// Whether the firmware only partially processed the message. Some tags may still carry a valid response,
+    /// see [`PropertyTagResponse::failed_tags`].
+    pub fn is_partial(&self) -> bool {
+        self.is_partial
+    }
+
+    /// The ids of the tags the firmware did not set the response bit for, i.e. that were not processed.
+    pub fn failed_tags(&self) -> Vec<u32> {
+        self.tags
+            .iter()
+            .filter(|tag| (self.buffer.read_u32(tag.buffer_offset + 8) & TAG_RESPONSE_BIT) == 0)
+            .map(|tag| tag.tag_id)
+            .collect()
+    }
+
+    /// Read the response value of the tag identified by `handle`. Fails if the firmware did not set the
+    /// response bit for this tag, i.e. it was not processed, or if `T` is larger than the value buffer the
+    /// tag was created with.
+    pub fn get<T: Copy>(&self, handle: TagHandle) -> MailboxResult<T> {
+        let tag = &self.tags[handle.0];
+        if size_of::<T>() as u32 > tag.value_buffer_size {
+            return Err(MailboxError::TagSizeMismatch);
+        }
+
+        let code_word = self.buffer.read_u32(tag.buffer_offset + 8);
+        if (code_word & TAG_RESPONSE_BIT) == 0 {
+            return Err(MailboxError::PartialResponse);
+        }
+
+        let bytes = self.buffer.read_bytes(tag.buffer_offset + 12, size_of::<T>());
+        let mut value = core::mem::MaybeUninit::<T>::uninit();
+        unsafe {
+            core::ptr::copy_nonoverlapping(bytes.as_ptr(), value.as_mut_ptr() as *mut u8, size_of::<T>());
+            Ok(value.assume_init())
+        }
+    }
+}
+
+fn align4(size: u32) -> u32 {
+    (size + 3) & !3
+}